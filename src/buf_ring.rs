@@ -0,0 +1,123 @@
+#![allow(dead_code)]
+
+use std::io;
+use std::sync::atomic::{AtomicU16, Ordering};
+use io_uring::IoUring;
+
+/// Size, in bytes, of each buffer handed out by a `BufRing`.
+pub const BUF_LEN: usize = 4096;
+
+/// Layout of a single ring entry, matching the kernel's `struct io_uring_buf`.
+///
+/// Slot `0`'s `tail` field doubles as the shared tail index of the whole
+/// ring (that's how `io_uring_buf_ring` overlays its header on the first
+/// entry), which is why `BufRing::release` only ever touches slot 0 to
+/// publish a new tail.
+#[repr(C)]
+struct BufRingEntry {
+    addr: u64,
+    len:  u32,
+    bid:  u16,
+    tail: u16,
+}
+
+/// A pool of fixed-size buffers registered with the kernel so that `Recv`
+/// can pick one automatically on completion instead of us allocating a
+/// fresh buffer on every read.
+///
+/// The ring of `BufRingEntry`s and the buffer data it points into live in
+/// a single boxed allocation, since the kernel needs one stable address to
+/// register against.
+pub struct BufRing {
+    /// Backing storage: `entries` ring entries followed by
+    /// `entries * BUF_LEN` bytes of buffer data.
+    mem: Box<[u8]>,
+
+    /// Buffer group id this ring is registered under.
+    bgid: u16,
+
+    /// Number of buffers in the ring. Always a power of two.
+    entries: u16,
+
+    /// Our local copy of the tail index; the shared one lives in `mem`.
+    tail: u16,
+}
+
+impl BufRing {
+    /// Allocate `entries` buffers of `BUF_LEN` bytes and register them with
+    /// `ring` under buffer-group id `bgid`. `entries` must be a power of two.
+    pub fn register(ring: &IoUring, bgid: u16, entries: u16) -> io::Result<Self> {
+        assert!(entries.is_power_of_two(), "`entries` must be a power of 2.");
+
+        let ring_bytes = entries as usize * std::mem::size_of::<BufRingEntry>();
+        let mem_len    = ring_bytes + entries as usize * BUF_LEN;
+        let mem        = vec![0u8; mem_len].into_boxed_slice();
+
+        let mut this = Self { mem, bgid, entries, tail: 0 };
+
+        unsafe {
+            ring.submitter().register_buf_ring(this.ring_addr(), entries, bgid)?;
+
+            // Seed every slot with its buffer and publish the tail in one go.
+            for bid in 0..entries {
+                this.set_slot(bid, bid);
+            }
+        }
+        this.advance(entries);
+
+        Ok(this)
+    }
+
+    /// The buffer-group id this ring was registered under.
+    pub fn bgid(&self) -> u16 {
+        self.bgid
+    }
+
+    /// Returns the buffer the kernel filled for `bid`.
+    pub fn buffer(&self, bid: u16) -> &[u8] {
+        let offset = self.data_offset(bid);
+        &self.mem[offset..offset + BUF_LEN]
+    }
+
+    /// Return buffer `bid` to the ring so the kernel can hand it out again.
+    pub fn release(&mut self, bid: u16) {
+        let slot = self.tail % self.entries;
+        unsafe { self.set_slot(slot, bid); }
+        self.advance(1);
+    }
+
+    /// Address of the first ring entry, as passed to the kernel.
+    fn ring_addr(&self) -> u64 {
+        self.mem.as_ptr() as u64
+    }
+
+    /// Byte offset of buffer `bid`'s data within `mem`.
+    fn data_offset(&self, bid: u16) -> usize {
+        self.entries as usize * std::mem::size_of::<BufRingEntry>()
+            + bid as usize * BUF_LEN
+    }
+
+    /// Point ring slot `slot` at buffer `bid`'s backing memory. Does not
+    /// publish the tail; callers must follow up with `advance`.
+    unsafe fn set_slot(&mut self, slot: u16, bid: u16) {
+        let offset = self.data_offset(bid);
+        let addr   = self.mem.as_mut_ptr().add(offset) as u64;
+
+        let entry = (self.mem.as_mut_ptr() as *mut BufRingEntry).add(slot as usize);
+        (*entry).addr = addr;
+        (*entry).len  = BUF_LEN as u32;
+        (*entry).bid  = bid;
+    }
+
+    /// Publish `count` newly-filled slots to the kernel with a release
+    /// store on the shared tail index.
+    fn advance(&mut self, count: u16) {
+        self.tail = self.tail.wrapping_add(count);
+
+        unsafe {
+            let head     = self.mem.as_mut_ptr() as *mut BufRingEntry;
+            let tail_ptr = std::ptr::addr_of_mut!((*head).tail) as *const AtomicU16;
+            (*tail_ptr).store(self.tail, Ordering::Release);
+        }
+    }
+}
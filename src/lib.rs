@@ -0,0 +1,8 @@
+mod server;
+mod slab;
+mod buf_ring;
+mod msg_hdr;
+
+pub use server::EchoServer;
+
+pub(crate) use slab::Slab;
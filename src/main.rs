@@ -30,17 +30,17 @@ fn main() -> io::Result<()> {
 
     let fork_res = fork(|| -> io::Result<()> {
         let mut server = EchoServer::new(CONNECTIONS, PORT)?;
-        loop {
-            server.tick()?;
+        while !server.tick()? {
             print!(","); // No flushing
         }
+        Ok(())
     });
     let fork_res2 = fork(|| -> io::Result<()> {
         let mut server = EchoServer::new(CONNECTIONS, PORT+1)?;
-        loop {
-            server.tick()?;
+        while !server.tick()? {
             print!("."); // No flushing
         }
+        Ok(())
     });
 
     println!("Got two forks: {fork_res:?} {fork_res2:?}");
@@ -0,0 +1,89 @@
+#![allow(dead_code)]
+
+use std::ffi::c_void;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+use libc::{c_int, iovec, msghdr, sockaddr_in, sockaddr_in6, sockaddr_storage, socklen_t};
+use libc::{AF_INET, AF_INET6};
+
+/// Size, in bytes, of the payload buffer embedded in every `MsgHdr`.
+const BUF_LEN: usize = 4096;
+
+/// Scratch space a `RecvMsg`/`SendMsg` SQE points the kernel at: the
+/// datagram's payload buffer, the peer address, and the `msghdr` tying
+/// them together.
+///
+/// Boxed because `hdr` points back into `addr` and `iov`; the box keeps
+/// those addresses stable across the async op no matter where the
+/// `MsgHdr` itself gets moved.
+pub struct MsgHdr {
+    addr: sockaddr_storage,
+    buf:  [u8; BUF_LEN],
+    iov:  iovec,
+    hdr:  msghdr,
+}
+
+impl MsgHdr {
+    /// Allocate a zeroed `MsgHdr` with its `msghdr` wired up to point at
+    /// its own `addr` and `buf` fields, ready for a `RecvMsg`.
+    pub fn new() -> Box<Self> {
+        let mut this = Box::new(Self {
+            addr: unsafe { std::mem::zeroed() },
+            buf:  [0u8; BUF_LEN],
+            iov:  unsafe { std::mem::zeroed() },
+            hdr:  unsafe { std::mem::zeroed() },
+        });
+
+        this.iov.iov_base = this.buf.as_mut_ptr() as *mut c_void;
+        this.iov.iov_len  = BUF_LEN;
+
+        this.hdr.msg_name    = &mut this.addr as *mut sockaddr_storage as *mut c_void;
+        this.hdr.msg_namelen = std::mem::size_of::<sockaddr_storage>() as socklen_t;
+        this.hdr.msg_iov     = &mut this.iov;
+        this.hdr.msg_iovlen  = 1;
+
+        this
+    }
+
+    /// Raw pointer to the `msghdr`, as the `RecvMsg`/`SendMsg` opcodes want.
+    pub fn as_mut_ptr(&mut self) -> *mut msghdr {
+        &mut self.hdr
+    }
+
+    /// The first `iov_len` bytes of the buffer: what a `SendMsg` will send
+    /// from. After a `RecvMsg` this is the whole buffer until
+    /// `set_payload_len` trims it to the received length.
+    pub fn payload(&self) -> &[u8] {
+        &self.buf[..self.iov.iov_len]
+    }
+
+    /// Shrink the iovec to `len` bytes so a follow-up `SendMsg` only
+    /// echoes back what `RecvMsg` actually received.
+    pub fn set_payload_len(&mut self, len: usize) {
+        self.iov.iov_len = len;
+    }
+
+    /// Grow the iovec back to the full buffer ahead of the next `RecvMsg`.
+    pub fn reset_payload_len(&mut self) {
+        self.iov.iov_len = BUF_LEN;
+    }
+
+    /// The peer address the kernel filled in during the last `RecvMsg`.
+    pub fn addr(&self) -> SocketAddr {
+        unsafe {
+            match self.addr.ss_family as c_int {
+                AF_INET => {
+                    let sa = &*(&self.addr as *const _ as *const sockaddr_in);
+                    let ip = Ipv4Addr::from(u32::from_be(sa.sin_addr.s_addr));
+                    SocketAddr::V4(SocketAddrV4::new(ip, u16::from_be(sa.sin_port)))
+                },
+                AF_INET6 => {
+                    let sa = &*(&self.addr as *const _ as *const sockaddr_in6);
+                    let ip = Ipv6Addr::from(sa.sin6_addr.s6_addr);
+                    SocketAddr::V6(SocketAddrV6::new(ip, u16::from_be(sa.sin6_port),
+                                                      sa.sin6_flowinfo, sa.sin6_scope_id))
+                },
+                family => panic!("unexpected address family `{family}`"),
+            }
+        }
+    }
+}
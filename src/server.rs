@@ -1,52 +1,152 @@
-use std::net::TcpListener;
+use std::net::{TcpListener, UdpSocket};
 use std::os::fd::{RawFd, AsRawFd};
 use std::io;
 use std::ptr::null_mut;
+use std::collections::VecDeque;
 use io_uring::types::Fd;
-use io_uring::{IoUring, opcode};
+use io_uring::{IoUring, Submitter, opcode, squeue, cqueue};
 use crate::Slab;
+use crate::buf_ring::BufRing;
+use crate::msg_hdr::MsgHdr;
 
 #[allow(dead_code)]
 #[derive(Clone, Debug)]
 enum OpType {
-    Accept,
-    Poll  { fd: RawFd, },
-    Read  { fd: RawFd, buf: Box<[u8]> },
-    Write { fd: RawFd, buf: Box<[u8]>, offset: usize, len: usize },
+    Poll { fd: RawFd, },
+    Recv { fd: RawFd, },
+    Send { fd: RawFd, bid: u16, offset: usize, len: usize },
+}
+
+/// UDP's counterpart to `OpType`: there's no accept/connection
+/// lifecycle for a datagram socket, so a token just cycles between these
+/// two instead of the three TCP states above.
+#[allow(dead_code)]
+enum UdpOpType {
+    /// Waiting on a datagram.
+    RecvFrom(Box<MsgHdr>),
+    /// Echoing a received datagram back to its sender.
+    SendTo(Box<MsgHdr>),
+    /// Transient placeholder used while a slot's `MsgHdr` is moved out for
+    /// the duration of a completion; always overwritten before the slot is
+    /// looked at again.
+    Empty,
+}
+
+/// The listener/socket a server drives: a TCP listener runs the
+/// accept/poll/read/write state machine, a UDP socket just bounces
+/// datagrams back to whoever sent them.
+enum Transport {
+    Tcp(TcpListener),
+    Udp(UdpSocket),
 }
 
 extern "C" {
     fn close(fd: i32) -> i32;
 }
 
-/// A server that echoes back everything it is sent.
+/// A server that echoes back everything it is sent, over TCP or UDP.
 pub struct EchoServer {
-    /// The internal TcpListener
-    _listener: TcpListener,
+    /// The listener/socket this server is driving.
+    transport: Transport,
 
-    /// The file descriptor of the internal TcpListener
+    /// The file descriptor of `transport`.
     fd: Fd,
 
     /// The amount of `accept`s we have to put into the `SubmissionQueue`
     /// in the internal io_uring. This is a tracking variable that gets
-    /// incremented on each `accept` push and decremented on completed `accept`s
+    /// decremented on each `accept` push and incremented on completed `accept`s.
+    ///
+    /// In multishot mode this is just 0 or 1: whether the single
+    /// `AcceptMulti` SQE needs (re-)arming. Unused in UDP mode.
     count: u16,
 
+    /// The `count` originally passed to `with_multishot`, kept around so a
+    /// fallback to one-shot accepts (see `multishot`) knows how many
+    /// `Accept` SQEs to re-arm instead of just one. Unused outside of that
+    /// fallback.
+    accept_target: u16,
+
+    /// Whether `push_accepts` submits a single self-re-arming `AcceptMulti`
+    /// SQE instead of one `Accept` SQE per expected connection. Flipped back
+    /// to `false` by `tick_tcp` if an `AcceptMulti` SQE ever fails, since
+    /// that means the running kernel doesn't support it. Unused in UDP mode.
+    multishot: bool,
+
     /// The internal io_uring state. This isn't directly used and is only here
     /// so that references to submitter, sq and cq don't get dropped
     ring: IoUring,
 
-    /// The mapping of tokens and their specific operations.
+    /// TCP connection state machine tokens. Unused in UDP mode.
     token_ops: Slab<OpType>,
+
+    /// UDP recv/send tokens. Unused in TCP mode.
+    udp_ops: Slab<UdpOpType>,
+
+    /// The pool of provided buffers `Recv` picks from in TCP mode, so we
+    /// don't have to allocate a fresh one on every read. UDP mode uses a
+    /// buffer embedded in each `MsgHdr` instead, so this is `None` there.
+    bufs: Option<BufRing>,
+
+    /// Entries that didn't fit on a full submission queue, retried on the
+    /// next `tick` instead of panicking under load.
+    backlog: VecDeque<squeue::Entry>,
+
+    /// Set by `shutdown`: no more `Accept`s (or re-armed receives) are
+    /// issued, and every live completion just gets closed out instead of
+    /// continuing its state machine.
+    draining: bool,
 }
 
 impl EchoServer {
+    /// Buffer-group id the provided-buffer ring is registered under.
+    const BGID: u16 = 0;
+
+    /// `user_data` tag put on the `AsyncCancel` SQEs `shutdown` submits, so
+    /// their own completions are recognized and skipped instead of being
+    /// looked up as a token.
+    const CANCEL_USR: u64 = u64::MAX;
+
+    /// `user_data` tag put on every `Accept`/`AcceptMulti` SQE. Accept
+    /// completions carry no per-op state (there's nothing to look up, just
+    /// an fd to hand off), so they're never stored in `token_ops` at all —
+    /// that keeps ordinary accept churn (including transient failures like
+    /// EMFILE/ECONNABORTED under load) from ever touching a `Slab`
+    /// generation. Multiple concurrent one-shot `Accept` SQEs share this
+    /// same tag; that's fine since none of them carry a slot to invalidate.
+    ///
+    /// `0` is safe to reserve this way because `Slab` never packs generation
+    /// `0` into a key (see `Slab`'s `generations` field), so no real token
+    /// can ever collide with it — including a fresh server's very first
+    /// accepted connection.
+    const ACCEPT_USR: u64 = 0;
+
     /// `count` - maximum number of connected clients.
     /// `port`  - port on which to start listening.
     ///
     /// The larger the `count`, the larger the internal io_uring queues.
     /// `count` must be a power of two.
+    ///
+    /// Accepts are one-shot: `push_accepts` re-submits an `Accept` SQE for
+    /// every connection it expects. See `with_multishot` for the
+    /// self-re-arming alternative, or `new_udp` for datagram mode.
     pub fn new(count: u16, port: u16) -> io::Result<Self> {
+        Self::new_inner(count, port, false)
+    }
+
+    /// Like `new`, but accepts are driven by a single multishot `AcceptMulti`
+    /// SQE that the kernel keeps re-arming on its own as connections come
+    /// in, instead of one `Accept` SQE per connection.
+    ///
+    /// There's no upfront capability probe: on a kernel without
+    /// multishot-accept support, the first `AcceptMulti` completion just
+    /// fails, and `tick_tcp` falls back to `new`'s one-shot `Accept` loop
+    /// from there on, re-arming up to `count` of them the same as if
+    /// `new` had been called directly.
+    pub fn with_multishot(count: u16, port: u16) -> io::Result<Self> {
+        Self::new_inner(count, port, true)
+    }
+
+    fn new_inner(count: u16, port: u16, multishot: bool) -> io::Result<Self> {
         // Validate the count
         assert!(count.is_power_of_two(), "`count` must be a power of 2.");
 
@@ -55,47 +155,133 @@ impl EchoServer {
         let ring = IoUring::new(ring_size)?;
 
         // Create the listener
-        let _listener = TcpListener::bind(("0.0.0.0", port))?;
-        let fd = Fd(_listener.as_raw_fd());
+        let listener = TcpListener::bind(("0.0.0.0", port))?;
+        let fd = Fd(listener.as_raw_fd());
+
+        // Accept completions aren't tracked here; see `ACCEPT_USR`.
+        let token_ops = Slab::with_capacity(ring_size.try_into().unwrap());
 
-        // In the beginning, all tokens are `accept`s
-        let mut token_ops = Slab::with_capacity(ring_size.try_into().unwrap());
+        // Register the provided-buffer pool `Recv` will pick from
+        let bufs = BufRing::register(&ring, Self::BGID, ring_size.try_into().unwrap())?;
 
-        // The first spot in the slab is reserved for `accept` opcodes
-        token_ops.insert(OpType::Accept);
+        // Multishot only ever needs a single SQE armed at a time. Keep the
+        // originally requested count around in case it later falls back to
+        // one-shot accepts.
+        let accept_target = count;
+        let count = if multishot { 1 } else { count };
 
         Ok(Self {
-            _listener,
+            transport: Transport::Tcp(listener),
             fd,
             count,
+            accept_target,
+            multishot,
             ring,
             token_ops,
+            udp_ops: Slab::with_capacity(0),
+            bufs: Some(bufs),
+            backlog: VecDeque::new(),
+            draining: false,
         })
     }
 
+    /// Bind a UDP socket on `port` and echo every datagram straight back to
+    /// whoever sent it. Unlike `new`, there's no accept/connection
+    /// lifecycle: each token independently cycles between `RecvFrom` and
+    /// `SendTo`, and `CONCURRENT_RECVS` of them are kept armed at once so a
+    /// datagram arriving while an earlier response is still being sent
+    /// doesn't just get dropped by the kernel.
+    pub fn new_udp(port: u16) -> io::Result<Self> {
+        const RING_ENTRIES: u32 = 16;
+
+        /// Number of `RecvMsg` tokens kept outstanding at once, mirroring
+        /// `count` for TCP's pool of outstanding `Accept`s.
+        const CONCURRENT_RECVS: usize = (RING_ENTRIES / 2) as usize;
+
+        let ring = IoUring::new(RING_ENTRIES)?;
+
+        let socket = UdpSocket::bind(("0.0.0.0", port))?;
+        let fd = Fd(socket.as_raw_fd());
+
+        let mut this = Self {
+            transport: Transport::Udp(socket),
+            fd,
+            count: 0,
+            accept_target: 0,
+            multishot: false,
+            ring,
+            token_ops: Slab::with_capacity(0),
+            udp_ops: Slab::with_capacity(RING_ENTRIES as usize),
+            bufs: None,
+            backlog: VecDeque::new(),
+            draining: false,
+        };
+
+        // Arm CONCURRENT_RECVS independent receives.
+        let mut sq = this.ring.submission();
+        for _ in 0..CONCURRENT_RECVS {
+            let key = this.udp_ops.insert(UdpOpType::RecvFrom(MsgHdr::new()));
+            let msg = match this.udp_ops.get_mut(key) {
+                Some(UdpOpType::RecvFrom(msg)) => msg.as_mut_ptr(),
+                _ => unreachable!(),
+            };
+            let recv = opcode::RecvMsg::new(this.fd, msg)
+                .build()
+                .user_data(key);
+            unsafe { sq.push(&recv).unwrap(); }
+        }
+        sq.sync();
+
+        Ok(this)
+    }
+
     /// Returns the current amount of possible connections that are not pushed
     /// into the submission queue. This number effectively shows you how many
-    /// connections *we are not accepting* but are supposed to.
+    /// connections *we are not accepting* but are supposed to. Always `0` in
+    /// UDP mode.
     pub fn count(&self) -> u16 {
         self.count
     }
 
-    /// Returns the primary TcpListener file desciptor of this server
+    /// Returns the primary file descriptor of this server: the `TcpListener`
+    /// in TCP mode, the `UdpSocket` in UDP mode.
     pub fn fd(&self) -> Fd {
         self.fd
     }
 
     /// Push as many `accept` opcodes into the submission ring as needed
-    /// (based on `self.count()`).
+    /// (based on `self.count()`). In multishot mode this arms the single
+    /// `AcceptMulti` SQE instead. No-op in UDP mode, which has no accepts,
+    /// and once `shutdown` has been called.
     fn push_accepts(&mut self) {
-        // Accept opcode
-        let accept = opcode::Accept::new(self.fd, null_mut(), null_mut())
-            .build()
-            .user_data(0);
+        if self.draining || matches!(self.transport, Transport::Udp(_)) {
+            return;
+        }
 
         // Get the submisison queue
         let mut sq = self.ring.submission();
 
+        if self.multishot {
+            if self.count > 0 {
+                let accept = opcode::AcceptMulti::new(self.fd)
+                    .build()
+                    .user_data(Self::ACCEPT_USR);
+
+                unsafe {
+                    if sq.push(&accept).is_ok() {
+                        self.count -= 1;
+                    }
+                }
+            }
+            sq.sync();
+            return;
+        }
+
+        // Accept opcode
+        let accept = opcode::Accept::new(self.fd, null_mut(), null_mut())
+            .build()
+            .user_data(Self::ACCEPT_USR);
+
         // Push as many accept opcodes into the queue as we can
         while self.count > 0 {
             unsafe {
@@ -108,11 +294,110 @@ impl EchoServer {
         sq.sync();
     }
 
-    /// Poll and handle the internal io_uring queues once. This is the function
-    /// used in the poll loop of the server.
-    pub fn tick(&mut self) -> io::Result<()> {
+    /// Submit and wait for at least one completion, swallowing the "ring
+    /// busy" error the kernel returns when the CQ is still full from a
+    /// previous tick.
+    fn submit_and_wait(submitter: &Submitter, to_wait: usize) -> io::Result<()> {
         const EBUSY: i32 = 16;
+
+        match submitter.submit_and_wait(to_wait) {
+            Ok(_)    => Ok(()),
+            Err(err) => match err.raw_os_error() {
+                Some(EBUSY) => Ok(()),
+                _ => Err(io::ErrorKind::Other)?,
+            },
+        }
+    }
+
+    /// Push `entry` onto `sq`, or stash it on `backlog` if the queue is
+    /// full so a later tick can retry it instead of panicking under load.
+    fn submit_or_backlog(
+        sq: &mut squeue::SubmissionQueue,
+        backlog: &mut VecDeque<squeue::Entry>,
+        entry: squeue::Entry,
+    ) {
+        unsafe {
+            if sq.push(&entry).is_err() {
+                backlog.push_back(entry);
+            }
+        }
+    }
+
+    /// Stop accepting new work and start tearing this server down: no more
+    /// `Accept`s (or, in UDP mode, re-armed receives) get issued, every
+    /// token currently outstanding gets an `AsyncCancel`, and the
+    /// listener/socket fd is closed so nothing new can come in. Keep
+    /// calling `tick` after this until it returns `true` to let in-flight
+    /// operations actually unwind.
+    pub fn shutdown(&mut self) -> io::Result<()> {
+        // IORING_ASYNC_CANCEL_ALL: cancel every pending request matching
+        // this user_data instead of just the first one found. Needed for
+        // ACCEPT_USR, since one-shot mode can have up to `count` concurrent
+        // `Accept` SQEs all sharing that same tag.
+        const ASYNC_CANCEL_ALL: i32 = 1 << 0;
+
+        self.draining = true;
+
+        let mut sq = self.ring.submission();
+        match self.transport {
+            Transport::Tcp(_) => {
+                // Accept/AcceptMulti isn't a Slab token (see `ACCEPT_USR`),
+                // so it needs its own cancel alongside everything in
+                // `token_ops`.
+                let cancel_accept = opcode::AsyncCancel::new(Self::ACCEPT_USR)
+                    .flags(ASYNC_CANCEL_ALL)
+                    .build()
+                    .user_data(Self::CANCEL_USR);
+                Self::submit_or_backlog(&mut sq, &mut self.backlog, cancel_accept);
+
+                for key in self.token_ops.keys() {
+                    let cancel = opcode::AsyncCancel::new(key)
+                        .build()
+                        .user_data(Self::CANCEL_USR);
+                    Self::submit_or_backlog(&mut sq, &mut self.backlog, cancel);
+                }
+            },
+            Transport::Udp(_) => {
+                for key in self.udp_ops.keys() {
+                    let cancel = opcode::AsyncCancel::new(key)
+                        .build()
+                        .user_data(Self::CANCEL_USR);
+                    Self::submit_or_backlog(&mut sq, &mut self.backlog, cancel);
+                }
+            },
+        }
+        sq.sync();
+
+        let raw_fd = match &self.transport {
+            Transport::Tcp(listener) => listener.as_raw_fd(),
+            Transport::Udp(socket)   => socket.as_raw_fd(),
+        };
+        unsafe { close(raw_fd); }
+
+        Ok(())
+    }
+
+    /// Whether `shutdown` has been called and we're still waiting for
+    /// in-flight operations to unwind.
+    pub fn is_draining(&self) -> bool {
+        self.draining
+    }
+
+    /// Poll and handle the internal io_uring queues once. This is the
+    /// function used in the poll loop of the server. Returns `true` once
+    /// `shutdown` has been called and every outstanding operation has
+    /// drained, at which point the caller should stop ticking.
+    pub fn tick(&mut self) -> io::Result<bool> {
+        match self.transport {
+            Transport::Tcp(_) => self.tick_tcp(),
+            Transport::Udp(_) => self.tick_udp(),
+        }
+    }
+
+    /// `tick`'s TCP path: the accept/poll/read/write state machine.
+    fn tick_tcp(&mut self) -> io::Result<bool> {
         const ECONNRESET: i32 = 104;
+        const ENOBUFS: i32 = 105;
         const POLLIN: u32 = 1;
 
         // Make sure we can accepts connections
@@ -121,40 +406,120 @@ impl EchoServer {
         // Split the ring into its internal components
         let (submitter, mut sq, mut cq) = self.ring.split();
 
-        // Wait for the completion queue to have some entries
-        match submitter.submit_and_wait(1) {
-            Ok(_)    => (),
-            Err(err) => match err.raw_os_error() {
-                Some(EBUSY) => (),
-                _ => Err(io::ErrorKind::Other)?,
-            },
+        // Drain anything that didn't fit on a previous tick before we touch
+        // new completions.
+        while let Some(entry) = self.backlog.pop_front() {
+            unsafe {
+                if sq.push(&entry).is_err() {
+                    self.backlog.push_front(entry);
+                    break;
+                }
+            }
         }
-        cq.sync();
+        sq.sync();
 
-        // TODO: Clean the backlog
+        // Still full after draining: submit now so there's room to make
+        // progress, instead of spinning on a full queue forever.
+        if !self.backlog.is_empty() {
+            Self::submit_and_wait(&submitter, 1)?;
+        }
+
+        // Wait for the completion queue to have some entries
+        Self::submit_and_wait(&submitter, 1)?;
+        cq.sync();
 
         // Go through each completion queue entry
         for cqe in &mut cq {
             let ret = cqe.result();
-            let usr = cqe.user_data().try_into().unwrap();
+            let usr = cqe.user_data();
+
+            // This is the completion of one of `shutdown`'s own `AsyncCancel`
+            // SQEs, not a token; the op it targeted reports its own
+            // completion (usually cancelled) separately.
+            if usr == Self::CANCEL_USR {
+                continue;
+            }
+
+            // Accept/AcceptMulti completions aren't Slab-tracked (see
+            // `ACCEPT_USR`), so they're handled entirely separately from
+            // the token state machine below.
+            if usr == Self::ACCEPT_USR {
+                // A failing `AcceptMulti` almost always means the running
+                // kernel doesn't support it, rather than routine churn:
+                // fall back to one-shot accepts instead of spinning a
+                // permanently-failing multishot SQE forever. `push_accepts`
+                // re-arms `accept_target` one-shot `Accept`s on the next
+                // tick, same as if `new` had been used from the start.
+                if ret < 0 && self.multishot {
+                    let err = io::Error::from_raw_os_error(-ret);
+                    eprintln!("AcceptMulti got error `{err}`, falling back to one-shot accepts");
+                    self.multishot = false;
+                    self.count = self.accept_target;
+                    continue;
+                }
+
+                // A failed one-shot accept (EMFILE, ENFILE, ECONNABORTED,
+                // ...) is routine under churn: log it and let
+                // `push_accepts` resubmit, same as a successful one.
+                let more = self.multishot && cqueue::more(cqe.flags());
+
+                if ret < 0 {
+                    let err = io::Error::from_raw_os_error(-ret);
+                    eprintln!("accept got error `{err}`");
+                } else if self.draining {
+                    unsafe { close(ret); }
+                } else {
+                    let token = self.token_ops.insert(OpType::Poll { fd: ret });
+                    let poll = opcode::PollAdd::new(Fd(ret), POLLIN)
+                        .build()
+                        .user_data(token);
+                    Self::submit_or_backlog(&mut sq, &mut self.backlog, poll);
+                }
+
+                if !more {
+                    self.count += 1;
+                }
+                continue;
+            }
 
             // Log any errors
             if ret < 0 {
                 let err = io::Error::from_raw_os_error(-ret);
 
+                // Out of provided buffers: the ring has none free for this
+                // fd right now. Don't tear the connection down, just go
+                // back to polling until one is returned.
+                if -ret == ENOBUFS {
+                    if let Some(&OpType::Recv { fd }) = self.token_ops.get(usr) {
+                        let poll = opcode::PollAdd::new(Fd(fd), POLLIN)
+                            .build()
+                            .user_data(usr);
+
+                        if let Some(token) = self.token_ops.get_mut(usr) {
+                            *token = OpType::Poll { fd };
+                        }
+
+                        Self::submit_or_backlog(&mut sq, &mut self.backlog, poll);
+                    }
+                    continue;
+                }
+
                 // Don't warn on errors like connection reset...
                 match -ret {
                     ECONNRESET => (),
                     __________ => eprintln!("Token `{usr}` got error `{err}`"),
                 }
 
-                // Close the file descriptor if we have one
+                // Close the file descriptor if we have one, releasing its
+                // buffer back to the ring first if it was still holding one.
                 match self.token_ops.get(usr).unwrap() {
-                    OpType::Poll {fd} | OpType::Read {fd,..}
-                    | OpType::Write {fd,..} => {
+                    OpType::Poll {fd} | OpType::Recv {fd,..} => {
+                        unsafe { close(*fd); }
+                    },
+                    OpType::Send {fd, bid, ..} => {
+                        self.bufs.as_mut().unwrap().release(*bid);
                         unsafe { close(*fd); }
                     },
-                    _ => (),
                 }
 
                 // Mark the user_data as free to use
@@ -171,64 +536,82 @@ impl EchoServer {
                 },
             };
 
+            // Draining: don't continue any token's state machine, just close
+            // out whatever fd it holds and free the slot.
+            if self.draining {
+                match optype {
+                    OpType::Poll { fd } | OpType::Recv { fd, .. } => {
+                        unsafe { close(*fd); }
+                    },
+                    OpType::Send { fd, bid, .. } => {
+                        self.bufs.as_mut().unwrap().release(*bid);
+                        unsafe { close(*fd); }
+                    },
+                }
+                self.token_ops.mark_free(usr);
+                continue;
+            }
+
             // Handle the operation.
             // XXX: Not too many comments from now on
             match optype.clone() {
-                OpType::Accept => {
-                    let token = self.token_ops.insert(OpType::Poll { fd: ret });
-
-                    let poll = opcode::PollAdd::new(Fd(ret), POLLIN)
-                        .build()
-                        .user_data(token.try_into().unwrap());
-
-                    // TODO: push to backlog
-                    unsafe { sq.push(&poll).unwrap(); }
-
-                    self.count += 1;
-                },
                 OpType::Poll { fd } => {
-                    let mut buf = vec![0u8; 4096].into_boxed_slice();
-
-                    let read = opcode::Recv::new(Fd(fd), buf.as_mut_ptr(),
-                                                 buf.len().try_into().unwrap())
+                    let recv = opcode::Recv::new(Fd(fd), null_mut(), 0)
+                        .buf_group(Self::BGID)
                         .build()
-                        .user_data(usr.try_into().unwrap());
+                        .flags(squeue::Flags::BUFFER_SELECT)
+                        .user_data(usr);
 
                     let token = match self.token_ops.get_mut(usr) {
                         Some(token) => token,
                         None        => continue,
                     };
 
-                    *token = OpType::Read { fd, buf };
+                    *token = OpType::Recv { fd };
 
-                    // TODO: push to backlog
-                    unsafe { sq.push(&read).unwrap(); }
+                    Self::submit_or_backlog(&mut sq, &mut self.backlog, recv);
                 },
-                OpType::Read { fd, buf } => {
+                OpType::Recv { fd } => {
                     if ret == 0 {
+                        // BUFFER_SELECT still picks a buffer on a 0-byte
+                        // read (clean peer shutdown), so it has to be
+                        // released here too or every ordinary disconnect
+                        // leaks one from the ring.
+                        if let Some(bid) = cqueue::buffer_select(cqe.flags()) {
+                            self.bufs.as_mut().unwrap().release(bid);
+                        }
+
                         println!("exit");
                         self.token_ops.mark_free(usr);
                         unsafe { close(fd); }
                         continue;
                     }
 
-                    let write = opcode::Send::new(Fd(fd), buf.as_ptr(),
-                                                  buf.len().try_into().unwrap())
+                    let bid = match cqueue::buffer_select(cqe.flags()) {
+                        Some(bid) => bid,
+                        None => {
+                            eprintln!("Token `{usr}` got a Recv completion without a buffer");
+                            continue;
+                        },
+                    };
+                    let len: usize = ret.try_into().unwrap();
+
+                    let write = opcode::Send::new(Fd(fd),
+                                                  self.bufs.as_ref().unwrap().buffer(bid).as_ptr(),
+                                                  len.try_into().unwrap())
                         .build()
-                        .user_data(usr.try_into().unwrap());
+                        .user_data(usr);
 
                     let token = match self.token_ops.get_mut(usr) {
                         Some(token) => token,
                         None        => continue,
                     };
 
-                    let len = ret.try_into().unwrap();
-                    *token = OpType::Write { fd, buf, offset: 0, len };
+                    *token = OpType::Send { fd, bid, offset: 0, len };
 
-                    // TODO: push to backlog
-                    unsafe { sq.push(&write).unwrap(); }
+                    Self::submit_or_backlog(&mut sq, &mut self.backlog, write);
                 },
-                OpType::Write { fd, buf, offset, len } => {
+                OpType::Send { fd, bid, offset, len } => {
                     let write_len: usize = ret.try_into().unwrap();
 
                     let token = match self.token_ops.get_mut(usr) {
@@ -237,26 +620,132 @@ impl EchoServer {
                     };
 
                     if offset + write_len >= len {
+                        self.bufs.as_mut().unwrap().release(bid);
+
                         let poll = opcode::PollAdd::new(Fd(fd), POLLIN)
                             .build()
-                            .user_data(usr.try_into().unwrap());
+                            .user_data(usr);
                         *token = OpType::Poll { fd };
-                        unsafe { sq.push(&poll).unwrap(); }
+                        Self::submit_or_backlog(&mut sq, &mut self.backlog, poll);
                         continue;
                     }
 
-                    let write = opcode::Send::new(Fd(fd), buf.as_ptr(),
-                            buf.len().try_into().unwrap())
+                    let write = opcode::Send::new(Fd(fd),
+                            self.bufs.as_ref().unwrap().buffer(bid).as_ptr(),
+                            len.try_into().unwrap())
                         .build()
-                        .user_data(usr.try_into().unwrap());
-                    *token = OpType::Write { fd, buf, offset, len };
+                        .user_data(usr);
+                    *token = OpType::Send { fd, bid, offset, len };
 
-                    // TODO: push to backlog
-                    unsafe { sq.push(&write).unwrap(); }
+                    Self::submit_or_backlog(&mut sq, &mut self.backlog, write);
                 },
             }
         }
 
-        Ok(())
+        Ok(self.draining && self.token_ops.is_empty())
+    }
+
+    /// `tick`'s UDP path: no accept/connection lifecycle, just a pool of
+    /// tokens that each independently cycle between `RecvFrom` and
+    /// `SendTo` to bounce datagrams back to their senders.
+    fn tick_udp(&mut self) -> io::Result<bool> {
+        let (submitter, mut sq, mut cq) = self.ring.split();
+
+        // Drain anything that didn't fit on a previous tick before we touch
+        // new completions.
+        while let Some(entry) = self.backlog.pop_front() {
+            unsafe {
+                if sq.push(&entry).is_err() {
+                    self.backlog.push_front(entry);
+                    break;
+                }
+            }
+        }
+        sq.sync();
+
+        if !self.backlog.is_empty() {
+            Self::submit_and_wait(&submitter, 1)?;
+        }
+
+        Self::submit_and_wait(&submitter, 1)?;
+        cq.sync();
+
+        for cqe in &mut cq {
+            let ret = cqe.result();
+            let usr = cqe.user_data();
+
+            if usr == Self::CANCEL_USR {
+                continue;
+            }
+
+            if ret < 0 {
+                let err = io::Error::from_raw_os_error(-ret);
+                eprintln!("Token `{usr}` got error `{err}`");
+
+                if self.draining {
+                    self.udp_ops.mark_free(usr);
+                    continue;
+                }
+
+                // Whatever state this token was in, just re-arm a fresh
+                // receive on it; one bad datagram shouldn't take the whole
+                // loop down.
+                if let Some(token) = self.udp_ops.get_mut(usr) {
+                    let mut msg = match std::mem::replace(token, UdpOpType::Empty) {
+                        UdpOpType::RecvFrom(msg) | UdpOpType::SendTo(msg) => msg,
+                        UdpOpType::Empty => continue,
+                    };
+                    msg.reset_payload_len();
+
+                    let recv = opcode::RecvMsg::new(self.fd, msg.as_mut_ptr())
+                        .build()
+                        .user_data(usr);
+
+                    *token = UdpOpType::RecvFrom(msg);
+                    Self::submit_or_backlog(&mut sq, &mut self.backlog, recv);
+                }
+                continue;
+            }
+
+            if self.udp_ops.get(usr).is_none() {
+                eprintln!("user_data {usr} not registered.");
+                continue;
+            }
+
+            // Draining: a receive or send landed anyway, racing with our
+            // cancel; just free the slot instead of cycling it again.
+            if self.draining {
+                self.udp_ops.mark_free(usr);
+                continue;
+            }
+
+            let token = self.udp_ops.get_mut(usr).unwrap();
+            match std::mem::replace(token, UdpOpType::Empty) {
+                UdpOpType::RecvFrom(mut msg) => {
+                    let len: usize = ret.try_into().unwrap();
+                    msg.set_payload_len(len);
+
+                    let send = opcode::SendMsg::new(self.fd, msg.as_mut_ptr())
+                        .build()
+                        .user_data(usr);
+
+                    *token = UdpOpType::SendTo(msg);
+                    Self::submit_or_backlog(&mut sq, &mut self.backlog, send);
+                },
+                UdpOpType::SendTo(mut msg) => {
+                    msg.reset_payload_len();
+
+                    let recv = opcode::RecvMsg::new(self.fd, msg.as_mut_ptr())
+                        .build()
+                        .user_data(usr);
+
+                    *token = UdpOpType::RecvFrom(msg);
+                    Self::submit_or_backlog(&mut sq, &mut self.backlog, recv);
+                },
+                UdpOpType::Empty => (),
+            }
+        }
+
+        Ok(self.draining && self.udp_ops.is_empty())
     }
 }
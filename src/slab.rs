@@ -1,7 +1,17 @@
 #![allow(dead_code)]
 
+/// Number of bits used for the index half of a packed key; the remaining
+/// low bits hold the slot's generation.
+const INDEX_SHIFT: u32 = 32;
+
 /// A vector tracked by a backing bitmap. You could call this a primitive
 /// allocator for uniform structures that supports in-place free.
+///
+/// Each slot also carries a generation counter, bumped every time it's
+/// freed. `insert` hands back a key with the slot's generation packed in,
+/// so a completion that names a slot after it's been freed and reused
+/// (and so carries a stale generation) is rejected instead of aliasing
+/// whatever now lives there.
 pub struct Slab<T> {
     /// The inner backing memory
     inner: Vec<T>,
@@ -13,6 +23,25 @@ pub struct Slab<T> {
     /// The second 8 entries in `inner` show that `inner[9]` and `inner[13]`
     /// are empty. `inner[16..]` is empty.
     bitmap: Vec<usize>,
+
+    /// Generation counter per slot, bumped on every `mark_free`. A key is
+    /// only honored while its packed generation matches the one stored
+    /// here for that index.
+    ///
+    /// Generation `0` is never used (slots start at generation `1`, and
+    /// `mark_free`'s bump skips back over `0` on wraparound), so `pack`
+    /// never produces the all-zero key `0` for any index. That leaves `0`
+    /// free for callers to use as a sentinel `user_data` distinct from
+    /// anything a `Slab` could ever hand out.
+    generations: Vec<u32>,
+
+    /// Intrusive free list: for a freed slot, the index of the next freed
+    /// slot (or `None` if it's the last one). Lets `insert` grab a free
+    /// spot in O(1) instead of scanning `bitmap`.
+    next_free: Vec<Option<usize>>,
+
+    /// Index of the first freed slot, or `None` if there isn't one.
+    free_head: Option<usize>,
 }
 
 impl<T> Slab<T> {
@@ -21,19 +50,24 @@ impl<T> Slab<T> {
         let bm_entries = cap / usize::BITS as usize + 1;
 
         Self {
-            inner:  Vec::with_capacity(cap),
-            bitmap: vec![0usize; bm_entries],
+            inner:       Vec::with_capacity(cap),
+            bitmap:      vec![0usize; bm_entries],
+            generations: vec![1u32; cap],
+            next_free:   Vec::with_capacity(cap),
+            free_head:   None,
         }
     }
 
-    /// Insert an `element` into the vector and return its index.
-    pub fn insert(&mut self, element: T) -> usize {
-        let idx = if let Some(idx) = self.get_free() {
-            // If we found a free spot, insert the element into it
+    /// Insert an `element` into the vector and return a key that identifies
+    /// both its slot and the slot's current generation.
+    pub fn insert(&mut self, element: T) -> u64 {
+        let idx = if let Some(idx) = self.free_head {
+            // Pop the free list's head and insert the element into it
+            self.free_head = self.next_free[idx];
             self.inner[idx] = element;
             idx
         } else {
-            // If we couldn't find one, allocate a new one.
+            // The free list is empty: allocate a new slot.
             let idx = self.inner.len();
             self.inner.push(element);
 
@@ -41,28 +75,72 @@ impl<T> Slab<T> {
             if idx == (self.bitmap.len() * usize::BITS as usize) {
                 self.bitmap.push(1);
             }
+
+            // Make sure we have a generation counter and free-list slot
+            // for this index
+            if idx == self.generations.len() {
+                self.generations.push(1);
+            }
+            self.next_free.push(None);
             idx
         };
 
         // Mark the bit as vacant
         self.mark_vacant(idx);
-        idx
+        Self::pack(idx, self.generations[idx])
     }
 
-    /// Returns a reference to an element if present
-    pub fn get(&self, idx: usize) -> Option<&T> {
-        if self.is_vacant(idx) { Some(&self.inner[idx]) } else { None }
+    /// Returns a reference to an element if `key`'s slot is occupied and
+    /// its generation still matches.
+    pub fn get(&self, key: u64) -> Option<&T> {
+        let (idx, gen) = Self::unpack(key);
+        (self.is_vacant(idx) && self.generations[idx] == gen).then(|| &self.inner[idx])
     }
 
-    /// Returns a mutable reference to an element if present
-    pub fn get_mut(&mut self, idx: usize) -> Option<&mut T> {
-        if self.is_vacant(idx) { Some(&mut self.inner[idx]) } else { None }
+    /// Returns a mutable reference to an element if `key`'s slot is
+    /// occupied and its generation still matches.
+    pub fn get_mut(&mut self, key: u64) -> Option<&mut T> {
+        let (idx, gen) = Self::unpack(key);
+        (self.is_vacant(idx) && self.generations[idx] == gen).then(|| &mut self.inner[idx])
     }
 
-    /// Marks the element at `idx` as free
-    pub fn mark_free(&mut self, idx: usize) {
+    /// Marks the element named by `key` as free, bumping its generation so
+    /// any other copy of this key becomes stale. No-ops if `key`'s
+    /// generation is already stale.
+    pub fn mark_free(&mut self, key: u64) {
+        let (idx, gen) = Self::unpack(key);
+        if self.generations[idx] != gen {
+            return;
+        }
+
         let (map_idx, bit_idx) = self.get_bitmap_idx(idx);
         self.bitmap[map_idx] &= !(1 << bit_idx);
+
+        // Bump the generation, skipping back over 0 on wraparound so it
+        // never lands on the one generation `pack` is never allowed to
+        // produce (see `generations`'s doc comment).
+        self.generations[idx] = match self.generations[idx].wrapping_add(1) {
+            0 => 1,
+            gen => gen,
+        };
+
+        // Push this slot onto the front of the free list
+        self.next_free[idx] = self.free_head;
+        self.free_head = Some(idx);
+    }
+
+    /// Iterate the keys of every currently-occupied slot, for callers that
+    /// need to act on everything a slab is holding (e.g. cancelling all
+    /// outstanding operations during shutdown).
+    pub fn keys(&self) -> impl Iterator<Item = u64> + '_ {
+        (0..self.inner.len())
+            .filter(|&idx| self.is_vacant(idx))
+            .map(|idx| Self::pack(idx, self.generations[idx]))
+    }
+
+    /// Whether every slot is currently free.
+    pub fn is_empty(&self) -> bool {
+        self.keys().next().is_none()
     }
 
     /// Marks the element at `idx` as vacant
@@ -71,21 +149,6 @@ impl<T> Slab<T> {
         self.bitmap[map_idx] |= 1 << bit_idx;
     }
 
-    /// Returns the index of the first uninhabited (free) spot in the vector.
-    fn get_free(&self) -> Option<usize> {
-        self.bitmap.iter().enumerate().find(|(_idx, bm)| {
-            // Try and find a free spot
-            bm.trailing_ones() != usize::BITS
-        })
-            // Convert the bit to an index into `self.inner`
-            .map(|(idx, bm)| {
-                idx * usize::BITS as usize + bm.trailing_ones() as usize
-            })
-
-            // If the index is past the end of `self.inner`, it's not allocated
-            .filter(|&idx| { idx < self.inner.len() })
-    }
-
     /// Checks whether the element at `idx` in the inner vector is vacant.
     ///
     /// Panics if `idx` is larger than the length of the allocated inner vector.
@@ -104,4 +167,130 @@ impl<T> Slab<T> {
         let map_idx = idx / usize::BITS as usize;
         (map_idx, bit_idx)
     }
+
+    /// Pack a slot index and its generation into a single key.
+    fn pack(idx: usize, gen: u32) -> u64 {
+        ((idx as u64) << INDEX_SHIFT) | gen as u64
+    }
+
+    /// Split a packed key back into its slot index and generation.
+    fn unpack(key: u64) -> (usize, u32) {
+        ((key >> INDEX_SHIFT) as usize, key as u32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Slab;
+
+    #[test]
+    fn insert_then_get() {
+        let mut slab = Slab::with_capacity(4);
+        let key = slab.insert(42);
+        assert_eq!(slab.get(key), Some(&42));
+    }
+
+    #[test]
+    fn first_ever_key_is_never_zero() {
+        // idx 0, generation 0 packs to 0, which callers reserve as a
+        // sentinel `user_data` distinct from anything the slab hands out.
+        // A brand-new slab's very first `insert` must not produce it.
+        let mut slab = Slab::with_capacity(4);
+        assert_ne!(slab.insert(()), 0);
+    }
+
+    #[test]
+    fn key_is_never_zero_after_generation_wraps() {
+        let mut slab = Slab::with_capacity(4);
+        let key = slab.insert(());
+        let (idx, _) = Slab::<()>::unpack(key);
+
+        // Force this slot right up to the edge of wraparound, then free it
+        // one more time: the bump from `u32::MAX` must skip back over `0`
+        // and land on `1`, not `0`.
+        slab.generations[idx] = u32::MAX;
+        slab.mark_free(Slab::<()>::pack(idx, u32::MAX));
+        assert_eq!(slab.generations[idx], 1);
+
+        let key = slab.insert(());
+        assert_ne!(key, 0);
+    }
+
+    #[test]
+    fn get_mut_sees_writes() {
+        let mut slab = Slab::with_capacity(4);
+        let key = slab.insert(1);
+        *slab.get_mut(key).unwrap() = 2;
+        assert_eq!(slab.get(key), Some(&2));
+    }
+
+    #[test]
+    fn mark_free_vacates_the_slot() {
+        let mut slab = Slab::with_capacity(4);
+        let key = slab.insert(1);
+        slab.mark_free(key);
+        assert_eq!(slab.get(key), None);
+        assert_eq!(slab.get_mut(key), None);
+    }
+
+    #[test]
+    fn stale_key_is_rejected_after_reuse() {
+        let mut slab = Slab::with_capacity(4);
+        let first = slab.insert(1);
+        slab.mark_free(first);
+
+        // The freed slot gets handed straight back out...
+        let second = slab.insert(2);
+        assert_eq!(slab.get(second), Some(&2));
+
+        // ...but the stale key from before the free must never alias it,
+        // even though it names the same underlying slot.
+        assert_eq!(slab.get(first), None);
+        assert_eq!(slab.get_mut(first), None);
+    }
+
+    #[test]
+    fn mark_free_on_a_stale_key_is_a_no_op() {
+        let mut slab = Slab::with_capacity(4);
+        let first = slab.insert(1);
+        slab.mark_free(first);
+        let second = slab.insert(2);
+
+        // A late/duplicate free using the old key must not touch the slot
+        // the key no longer owns.
+        slab.mark_free(first);
+        assert_eq!(slab.get(second), Some(&2));
+    }
+
+    #[test]
+    fn free_list_reuses_most_recently_freed_slot_first() {
+        let mut slab = Slab::with_capacity(4);
+        let a = slab.insert('a');
+        let b = slab.insert('b');
+
+        slab.mark_free(a);
+        slab.mark_free(b);
+
+        // free_head is a LIFO stack: the last slot freed (`b`'s) is the
+        // first one handed back out.
+        let reused = slab.insert('c');
+        assert_eq!(super::Slab::<char>::unpack(reused).0, super::Slab::<char>::unpack(b).0);
+    }
+
+    #[test]
+    fn keys_and_is_empty_reflect_live_slots() {
+        let mut slab = Slab::with_capacity(4);
+        assert!(slab.is_empty());
+
+        let a = slab.insert(1);
+        let b = slab.insert(2);
+        assert!(!slab.is_empty());
+        assert_eq!(slab.keys().count(), 2);
+
+        slab.mark_free(a);
+        assert_eq!(slab.keys().count(), 1);
+
+        slab.mark_free(b);
+        assert!(slab.is_empty());
+    }
 }